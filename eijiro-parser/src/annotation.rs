@@ -0,0 +1,153 @@
+//! Typed representation of the inline markers Eijiro embeds in explanation
+//! and complement text: `【...】` category tags (e.g. `【参考】`, `【複】`,
+//! `【発音】`, `【語源】`), `《...》` domain/field labels, `〈...〉` register
+//! labels, and `〔...〕` usage-context notes.
+//!
+//! [`extract`] pulls these out of a raw body string, returning the cleaned
+//! display text alongside the annotations found in it, so callers no longer
+//! have to pick markers back out of a flat string. Each extracted
+//! [`AnnotatedSpan`] records where in the cleaned text it originally sat, so
+//! [`reinsert`] can put it back in place rather than always prepending it.
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Eq, PartialEq, Ord, PartialOrd, Serialize, Deserialize)]
+pub enum Annotation {
+    /// A `【kind】value` tag, e.g. `【参考】autocomplete` -> `kind: "参考"`,
+    /// `value: "autocomplete"`. `value` runs until the next marker or the
+    /// end of the text.
+    Category { kind: String, value: String },
+    /// A `《domain》` field label, e.g. `《コ》`.
+    Domain(String),
+    /// A `〈register〉` label, e.g. `〈話〉`.
+    Register(String),
+    /// A `〔note〕` usage-context note.
+    Context(String),
+}
+
+/// An [`Annotation`] together with the byte offset into the cleaned text
+/// where it originally occurred, so it can be reinserted in place.
+#[derive(Debug, Clone, Eq, PartialEq, Ord, PartialOrd, Serialize, Deserialize)]
+pub struct AnnotatedSpan {
+    pub offset: usize,
+    pub annotation: Annotation,
+}
+
+const OPEN_MARKERS: [char; 4] = ['【', '《', '〈', '〔'];
+
+/// Strips the recognized markers out of `text`, returning the cleaned
+/// display text and the annotations extracted from it, in the order they
+/// appeared (and therefore non-decreasing by `offset`).
+pub fn extract(text: &str) -> (String, Vec<AnnotatedSpan>) {
+    let mut clean = String::with_capacity(text.len());
+    let mut annotations = Vec::new();
+    let mut rest = text;
+
+    while let Some(c) = rest.chars().next() {
+        let close = match c {
+            '【' => '】',
+            '《' => '》',
+            '〈' => '〉',
+            '〔' => '〕',
+            _ => {
+                clean.push(c);
+                rest = &rest[c.len_utf8()..];
+                continue;
+            }
+        };
+        let open_len = c.len_utf8();
+        let end = match rest[open_len..].find(close) {
+            Some(end) => open_len + end,
+            None => {
+                // Unterminated marker: treat the opening bracket as plain text.
+                clean.push(c);
+                rest = &rest[open_len..];
+                continue;
+            }
+        };
+        let content = &rest[open_len..end];
+        rest = &rest[end + close.len_utf8()..];
+        let offset = clean.len();
+        let annotation = match c {
+            '【' => {
+                let value_end = rest.find(OPEN_MARKERS).unwrap_or(rest.len());
+                let value = &rest[..value_end];
+                rest = &rest[value_end..];
+                Annotation::Category {
+                    kind: content.to_string(),
+                    value: value.to_string(),
+                }
+            }
+            '《' => Annotation::Domain(content.to_string()),
+            '〈' => Annotation::Register(content.to_string()),
+            '〔' => Annotation::Context(content.to_string()),
+            _ => unreachable!(),
+        };
+        annotations.push(AnnotatedSpan { offset, annotation });
+    }
+
+    (clean, annotations)
+}
+
+/// Reinserts `annotations` into `clean` at the offsets [`extract`]
+/// recorded, formatting each one back to marker text with `format`. This
+/// is the inverse of `extract`: `reinsert(&extract(text).0, &extract(text).1,
+/// format)` recovers `text` (byte-for-byte, given the same `format` extract
+/// itself would use to print a marker).
+pub fn reinsert<F: Fn(&Annotation) -> String>(
+    clean: &str,
+    annotations: &[AnnotatedSpan],
+    format: F,
+) -> String {
+    let mut out = String::with_capacity(clean.len());
+    let mut last = 0;
+    for span in annotations {
+        out.push_str(&clean[last..span.offset]);
+        out.push_str(&format(&span.annotation));
+        last = span.offset;
+    }
+    out.push_str(&clean[last..]);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fmt(a: &Annotation) -> String {
+        match a {
+            Annotation::Category { kind, value } => format!("【{}】{}", kind, value),
+            Annotation::Domain(d) => format!("《{}》", d),
+            Annotation::Register(r) => format!("〈{}〉", r),
+            Annotation::Context(c) => format!("〔{}〕", c),
+        }
+    }
+
+    #[test]
+    fn round_trips_leading_marker() {
+        let (clean, annotations) = extract("《コ》自動補完");
+        assert_eq!(clean, "自動補完");
+        assert_eq!(reinsert(&clean, &annotations, fmt), "《コ》自動補完");
+    }
+
+    #[test]
+    fn round_trips_marker_mid_string() {
+        let (clean, annotations) = extract("前置き〔注釈〕続き");
+        assert_eq!(clean, "前置き続き");
+        assert_eq!(reinsert(&clean, &annotations, fmt), "前置き〔注釈〕続き");
+    }
+
+    #[test]
+    fn round_trips_category_tag_with_trailing_value() {
+        let (clean, annotations) = extract("【参考】autocomplete");
+        assert_eq!(clean, "");
+        assert_eq!(reinsert(&clean, &annotations, fmt), "【参考】autocomplete");
+    }
+
+    #[test]
+    fn round_trips_multiple_markers_in_order() {
+        let (clean, annotations) = extract("a〈話〉b〔注〕c");
+        assert_eq!(clean, "abc");
+        assert_eq!(reinsert(&clean, &annotations, fmt), "a〈話〉b〔注〕c");
+    }
+}
@@ -1,17 +1,43 @@
 use anyhow::{anyhow, Result};
 use fst::{Map, MapBuilder};
-use lazy_static::lazy_static;
-use regex::Regex;
 use serde::de::{Deserializer, SeqAccess, Visitor};
 use serde::ser::{SerializeStruct, Serializer};
 use serde::{Deserialize, Serialize};
 
 pub extern crate fst;
 
+mod annotation;
+mod parser;
+mod query;
+mod reverse;
+mod storage;
+pub use annotation::{reinsert as reinsert_annotations, AnnotatedSpan, Annotation};
+pub use parser::ParseError;
+pub use query::{filter, parse as parse_query, Predicate};
+pub use reverse::{query_ngrams, DEFAULT_NGRAM_WIDTHS};
+pub use storage::{build as build_lazy, LazyDict};
+
+/// Memory-maps a dictionary previously written with [`build_lazy`],
+/// decoding each entry's fields lazily as queries match them. See
+/// [`LazyDict`] for the on-disk layout and [`parse`] for the eager
+/// alternative used for small inputs.
+pub fn open<P: AsRef<std::path::Path>>(path_prefix: P) -> Result<LazyDict> {
+    LazyDict::open(path_prefix)
+}
+
 #[derive(Debug)]
 pub struct Dict {
+    /// English headword -> `fields`/`headwords` index.
     pub keys: Map<Vec<u8>>,
     pub fields: Vec<Vec<Field>>,
+    /// `headwords[i]` is the English headword for `fields[i]`.
+    pub headwords: Vec<String>,
+    /// Japanese n-grams, keyed by an offset into `reverse_postings`.
+    pub reverse: Map<Vec<u8>>,
+    /// `reverse_postings[offset]` is the sorted, deduped list of
+    /// `(headword_idx, field_idx)` pairs into `fields` whose explanation or
+    /// examples contain that n-gram.
+    pub reverse_postings: Vec<Vec<(u64, u64)>>,
 }
 #[derive(Debug, Eq, PartialEq, Ord, PartialOrd, Serialize, Deserialize)]
 pub struct Field {
@@ -22,6 +48,7 @@ pub struct Field {
 #[derive(Debug, Eq, PartialEq, Ord, PartialOrd, Serialize, Deserialize)]
 pub struct Explanation {
     pub body: String,
+    pub annotations: Vec<AnnotatedSpan>,
     pub complements: Vec<Complement>,
 }
 #[derive(Debug, Eq, PartialEq, Ord, PartialOrd, Serialize, Deserialize)]
@@ -32,6 +59,7 @@ pub struct Example {
 #[derive(Debug, Eq, PartialEq, Ord, PartialOrd, Serialize, Deserialize)]
 pub struct Complement {
     pub body: String,
+    pub annotations: Vec<AnnotatedSpan>,
 }
 
 impl Serialize for Dict {
@@ -39,10 +67,14 @@ impl Serialize for Dict {
     where
         S: Serializer,
     {
-        let mut seq = serializer.serialize_struct("Dict", 2)?;
+        let mut seq = serializer.serialize_struct("Dict", 5)?;
         let keys_bytes = self.keys.clone().into_fst().into_inner();
         seq.serialize_field("keys", &keys_bytes)?;
         seq.serialize_field("fields", &self.fields)?;
+        seq.serialize_field("headwords", &self.headwords)?;
+        let reverse_bytes = self.reverse.clone().into_fst().into_inner();
+        seq.serialize_field("reverse", &reverse_bytes)?;
+        seq.serialize_field("reverse_postings", &self.reverse_postings)?;
         seq.end()
     }
 }
@@ -70,107 +102,94 @@ impl<'de> Deserialize<'de> for Dict {
                 let fields = seq
                     .next_element()?
                     .ok_or_else(|| de_err::invalid_length(1, &self))?;
-                Ok(Dict { keys, fields })
+                let headwords = seq
+                    .next_element()?
+                    .ok_or_else(|| de_err::invalid_length(2, &self))?;
+                let reverse_bytes: Vec<u8> = seq
+                    .next_element()?
+                    .ok_or_else(|| de_err::invalid_length(3, &self))?;
+                let reverse = Map::new(reverse_bytes).unwrap();
+                let reverse_postings = seq
+                    .next_element()?
+                    .ok_or_else(|| de_err::invalid_length(4, &self))?;
+                Ok(Dict {
+                    keys,
+                    fields,
+                    headwords,
+                    reverse,
+                    reverse_postings,
+                })
             }
         }
-        deserializer.deserialize_struct("Dict", &["keys", "fields"], DictVisitor)
-    }
-}
-
-fn parse_complements(text: &str) -> Result<Vec<Complement>> {
-    lazy_static! {
-        static ref COMPLEMENT: Regex = Regex::new(r#"◆([^◆■]+)"#).unwrap();
+        deserializer.deserialize_struct(
+            "Dict",
+            &["keys", "fields", "headwords", "reverse", "reverse_postings"],
+            DictVisitor,
+        )
     }
-    COMPLEMENT
-        .captures_iter(text)
-        .map(|m| {
-            Ok(Complement {
-                body: m
-                    .get(1)
-                    .ok_or(anyhow!("Invalid complement format"))?
-                    .as_str()
-                    .to_string(),
-            })
-        })
-        .collect()
 }
 
-fn parse_examples(text: &str) -> Result<Vec<Example>> {
-    lazy_static! {
-        static ref EXAMPLE: Regex = Regex::new(r#"■([^◆■]+)(?P<complements>(◆[^◆■]+)+)?"#).unwrap();
+/// Parses `text` into a [`Dict`], indexing the Japanese reverse lookup with
+/// the given n-gram widths (see [`reverse::build`]).
+pub fn parse_with_ngrams(text: &str, ngram_widths: &[usize]) -> Result<Dict> {
+    let mut tmp = Vec::new();
+    let mut errors = Vec::new();
+    for (line_no, line) in text.lines().enumerate() {
+        if line.is_empty() {
+            continue;
+        }
+        match parser::parse_line(line_no + 1, line) {
+            Ok((k, f)) => tmp.push((k, f, line_no)),
+            Err(e) => errors.push(e),
+        }
     }
-    EXAMPLE
-        .captures_iter(text)
-        .map(|m| {
-            Ok(Example {
-                sentence: m
-                    .get(1)
-                    .ok_or(anyhow!("Invalid example format"))?
-                    .as_str()
-                    .to_string(),
-                complements: m
-                    .name("complements")
-                    .map(|m| parse_complements(m.as_str()))
-                    .unwrap_or(Ok(Vec::new()))?,
-            })
-        })
-        .collect()
-}
-
-fn parse_field(text: &str) -> Result<(String, Field)> {
-    lazy_static! {
-        static ref RE: Regex = Regex::new(
-            r#"■(?P<item>.+?)(?: +\{(?P<ident>.+)\})? : (?P<exp>[^◆■]*)(?P<complements>(?:◆[^◆■]+)*)(?P<examples>(■.+)*)"#
-        )
-        .unwrap();
+    if !errors.is_empty() {
+        let report = errors
+            .iter()
+            .map(|e| e.to_string())
+            .collect::<Vec<_>>()
+            .join("\n");
+        return Err(anyhow!(
+            "failed to parse {} line(s):\n{}",
+            errors.len(),
+            report
+        ));
     }
-    let cap = RE.captures(text).ok_or(anyhow!("Invalid field format"))?;
-    let key = cap["item"].to_string();
-    Ok((
-        key,
-        Field {
-            ident: cap.name("ident").map(|m| m.as_str().to_string()),
-            explanation: {
-                Explanation {
-                    body: cap["exp"].to_string(),
-                    complements: parse_complements(&cap["complements"])?,
-                }
-            },
-            examples: parse_examples(&cap["examples"])?,
-        },
-    ))
-}
-
-pub fn parse(text: &str) -> Result<Dict> {
-    let mut tmp = text
-        .lines()
-        .enumerate()
-        .map(|(line_no, line)| {
-            let (k, f) = parse_field(line).map_err(|e| anyhow!("line {}: {}", line_no, e))?;
-            Ok((k, f, line_no))
-        })
-        .collect::<Result<Vec<_>>>()?;
     tmp.sort();
 
     let mut map = MapBuilder::memory();
     let mut prev_key: Option<String> = None;
     let mut fields = Vec::new();
+    let mut headwords = Vec::new();
     for (k, f, line_no) in tmp.into_iter() {
         let new_key = prev_key.as_ref().map(|p| p != &k).unwrap_or(true);
         if new_key {
             map.insert(&k, fields.len() as u64)
                 .map_err(|e| anyhow!("[line {}]: {}", line_no, e))?;
             fields.push(Vec::new());
+            headwords.push(k.clone());
             prev_key = Some(k);
         }
         fields.last_mut().unwrap().push(f);
     }
+
+    let (reverse, reverse_postings) = reverse::build(&fields, ngram_widths);
+
     Ok(Dict {
         keys: map.into_map(),
         fields,
+        headwords,
+        reverse,
+        reverse_postings,
     })
 }
 
+/// Parses `text` into a [`Dict`], indexing the Japanese reverse lookup with
+/// [`DEFAULT_NGRAM_WIDTHS`].
+pub fn parse(text: &str) -> Result<Dict> {
+    parse_with_ngrams(text, DEFAULT_NGRAM_WIDTHS)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -186,29 +205,39 @@ mod tests {
         ret
     }
 
+    fn com<S: Into<String>>(body: S, annotations: Vec<AnnotatedSpan>) -> Complement {
+        Complement {
+            body: body.into(),
+            annotations,
+        }
+    }
+
+    fn ann(annotation: Annotation) -> AnnotatedSpan {
+        AnnotatedSpan {
+            offset: 0,
+            annotation,
+        }
+    }
+
     fn new_field<S: Into<String>>(
         ident: Option<S>,
         exp: S,
-        exp_coms: Vec<S>,
-        examples: Vec<(S, Vec<S>)>,
+        exp_annotations: Vec<AnnotatedSpan>,
+        exp_coms: Vec<Complement>,
+        examples: Vec<(S, Vec<Complement>)>,
     ) -> Field {
         Field {
             ident: ident.map(|s| s.into()),
             explanation: Explanation {
                 body: exp.into(),
-                complements: exp_coms
-                    .into_iter()
-                    .map(|s| Complement { body: s.into() })
-                    .collect(),
+                annotations: exp_annotations,
+                complements: exp_coms,
             },
             examples: examples
                 .into_iter()
                 .map(|(s, c)| Example {
                     sentence: s.into(),
-                    complements: c
-                        .into_iter()
-                        .map(|c| Complement { body: c.into() })
-                        .collect(),
+                    complements: c,
                 })
                 .collect(),
         }
@@ -224,8 +253,18 @@ mod tests {
                 "autocompletion".to_string(),
                 &vec![new_field(
                     Some("名"),
-                    "《コ》〔入力文字の〕自動補完、オートコンプリート",
-                    vec!["【参考】autocomplete"],
+                    "自動補完、オートコンプリート",
+                    vec![
+                        ann(Annotation::Domain("コ".to_string())),
+                        ann(Annotation::Context("入力文字の".to_string()))
+                    ],
+                    vec![com(
+                        "",
+                        vec![ann(Annotation::Category {
+                            kind: "参考".to_string(),
+                            value: "autocomplete".to_string()
+                        })]
+                    )],
                     vec![]
                 )]
             )]
@@ -243,8 +282,18 @@ mod tests {
                 "selfie".to_string(),
                 &vec![new_field(
                     Some("名"),
-                    "〈話〉セルフィー、自撮り（の）写真",
-                    vec!["自分で撮影した自分の写真", "【複】selfies"],
+                    "セルフィー、自撮り（の）写真",
+                    vec![ann(Annotation::Register("話".to_string()))],
+                    vec![
+                        com("自分で撮影した自分の写真", vec![]),
+                        com(
+                            "",
+                            vec![ann(Annotation::Category {
+                                kind: "複".to_string(),
+                                value: "selfies".to_string()
+                            })]
+                        )
+                    ],
                     vec![]
                 )]
             )]
@@ -261,8 +310,9 @@ mod tests {
             "awkward silence".to_string(),
             &vec![new_field(
                 Some("1"),
-                "《an ～》気まずい［ぎこちない］沈黙",
-                vec!["「会話が不自然に途切れた気まずい時間」を指す。1回・2回と数えられるので可算。"],
+                "気まずい［ぎこちない］沈黙",
+                vec![ann(Annotation::Domain("an ～".to_string()))],
+                vec![com("「会話が不自然に途切れた気まずい時間」を指す。1回・2回と数えられるので可算。", vec![])],
                 vec![
                     ("・There was an awkward silence for a few seconds. 数秒間の気まずい沈黙がありました。", vec![]),
                     ("・There was an awkward silence for a moment. ちょっとの間、気まずい沈黙がありました。／一瞬、微妙な空気が流れた。", vec![])
@@ -283,7 +333,8 @@ mod tests {
             &vec![new_field(
                 Some("2"),
                 "気まずい沈黙状態",
-                vec!["「誰もしゃべらない状態」を表す。不可算。"],
+                vec![],
+                vec![com("「誰もしゃべらない状態」を表す。不可算。", vec![])],
                 vec![("・We stared at each other in awkward silence. 私たちは、気まずいムードで黙って顔を見合わせました。", vec![])]
             )]
         )]
@@ -301,8 +352,9 @@ mod tests {
                 &vec![new_field(
                     None,
                     "aaa",
-                    vec!["bbb", "ccc"],
-                    vec![("ddd", vec!["eee"]), ("fff", vec![])]
+                    vec![],
+                    vec![com("bbb", vec![]), com("ccc", vec![])],
+                    vec![("ddd", vec![com("eee", vec![])]), ("fff", vec![])]
                 )]
             )]
         )
@@ -321,8 +373,48 @@ mod tests {
                 &vec![new_field(
                     None,
                     "aaa",
-                    vec!["bbb", "ccc"],
-                    vec![("ddd", vec!["eee"]), ("fff", vec![])]
+                    vec![],
+                    vec![com("bbb", vec![]), com("ccc", vec![])],
+                    vec![("ddd", vec![com("eee", vec![])]), ("fff", vec![])]
+                )]
+            )]
+        )
+    }
+
+    #[test]
+    fn parse_error_has_precise_line_and_col() {
+        // No " : " separator anywhere, so the parser should fail right
+        // after the '■' marker, where it starts looking for one.
+        let err = crate::parser::parse_line(7, "■word with no separator").unwrap_err();
+        assert_eq!(err.line, 7);
+        assert_eq!(err.col, '■'.len_utf8() + 1);
+    }
+
+    #[test]
+    fn accumulates_errors_across_multiple_bad_lines() {
+        let s = "■bad one\n■bad two";
+        let msg = crate::parse(s).unwrap_err().to_string();
+        assert!(msg.contains("failed to parse 2 line(s)"), "{}", msg);
+        assert!(msg.contains("line 1,"), "{}", msg);
+        assert!(msg.contains("line 2,"), "{}", msg);
+    }
+
+    #[test]
+    fn headword_with_brace_not_preceded_by_space_is_plain_text() {
+        // A '{' that isn't part of " {ident}" (i.e. not directly preceded
+        // by a space) is just part of the headword, not an ident marker.
+        let s = "■C{braces} : a brace literal in the headword";
+        let dict = crate::parse(s).unwrap();
+        assert_eq!(
+            kv_vec(&dict),
+            vec![(
+                "C{braces}".to_string(),
+                &vec![new_field(
+                    None,
+                    "a brace literal in the headword",
+                    vec![],
+                    vec![],
+                    vec![]
                 )]
             )]
         )
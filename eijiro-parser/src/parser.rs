@@ -0,0 +1,159 @@
+//! nom combinators for the Eijiro entry grammar.
+//!
+//! A line looks like:
+//!
+//!   ■headword {ident} : explanation◆complement■example◆complement■example
+//!
+//! `parse_line` runs the grammar against a single line and turns any nom
+//! failure into a [`ParseError`] carrying the 1-based line/column of the
+//! byte that the parser got stuck on, instead of the single opaque message
+//! the old regex-based parser produced.
+
+use nom::{
+    bytes::complete::{tag, take_till},
+    character::complete::char,
+    combinator::map,
+    error::{context, VerboseError, VerboseErrorKind},
+    multi::many0,
+    sequence::preceded,
+    IResult, Offset,
+};
+
+use crate::{annotation, Complement, Example, Explanation, Field};
+
+type Res<'a, T> = IResult<&'a str, T, VerboseError<&'a str>>;
+
+#[derive(Debug)]
+pub struct ParseError {
+    pub line: usize,
+    pub col: usize,
+    pub message: String,
+}
+
+impl std::fmt::Display for ParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "line {}, col {}: {}", self.line, self.col, self.message)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+fn is_marker(c: char) -> bool {
+    c == '◆' || c == '■'
+}
+
+fn complement(input: &str) -> Res<'_, Complement> {
+    map(preceded(char('◆'), take_till(is_marker)), |raw: &str| {
+        let (body, annotations) = annotation::extract(raw);
+        Complement { body, annotations }
+    })(input)
+}
+
+fn complements(input: &str) -> Res<'_, Vec<Complement>> {
+    many0(complement)(input)
+}
+
+fn example(input: &str) -> Res<'_, Example> {
+    let (input, _) = char('■')(input)?;
+    let (input, sentence) = take_till(is_marker)(input)?;
+    let (input, complements) = complements(input)?;
+    Ok((
+        input,
+        Example {
+            sentence: sentence.to_string(),
+            complements,
+        },
+    ))
+}
+
+fn examples(input: &str) -> Res<'_, Vec<Example>> {
+    many0(example)(input)
+}
+
+/// Splits `headword {ident} : ` into the headword and the optional ident,
+/// mirroring the old regex's `(?P<item>.+?)(?: +\{(?P<ident>.+)\})? : `:
+/// the ident, if present, sits directly in front of the `" : "` separator.
+fn headword_and_ident(input: &str) -> Res<'_, (&str, Option<&str>)> {
+    let sep = match input.find(" : ") {
+        Some(idx) => idx,
+        None => {
+            return Err(nom::Err::Error(VerboseError {
+                errors: vec![(input, VerboseErrorKind::Context("':' separator"))],
+            }))
+        }
+    };
+    let prefix = &input[..sep];
+    let rest = &input[sep..];
+    if prefix.ends_with('}') {
+        if let Some(brace) = prefix.rfind(" {") {
+            let head = &prefix[..brace];
+            let ident = &prefix[brace + 2..prefix.len() - 1];
+            return Ok((rest, (head, Some(ident))));
+        }
+    }
+    Ok((rest, (prefix, None)))
+}
+
+fn entry(input: &str) -> Res<'_, (String, Field)> {
+    let (input, _) = context("'■' marker", char('■'))(input)?;
+    let (input, (head, ident)) = headword_and_ident(input)?;
+    let (input, _) = context("':' separator", tag(" : "))(input)?;
+    let (input, exp_raw) = take_till(is_marker)(input)?;
+    let (input, exp_complements) = complements(input)?;
+    let (input, examples) = examples(input)?;
+    let (exp_body, exp_annotations) = annotation::extract(exp_raw);
+    Ok((
+        input,
+        (
+            head.to_string(),
+            Field {
+                ident: ident.map(|s| s.to_string()),
+                explanation: Explanation {
+                    body: exp_body,
+                    annotations: exp_annotations,
+                    complements: exp_complements,
+                },
+                examples,
+            },
+        ),
+    ))
+}
+
+fn describe(kind: &VerboseErrorKind) -> String {
+    match kind {
+        VerboseErrorKind::Context(ctx) => format!("expected {}", ctx),
+        VerboseErrorKind::Char(c) => format!("expected '{}'", c),
+        VerboseErrorKind::Nom(k) => format!("{:?}", k),
+    }
+}
+
+/// Parses a single line of `EIJIRO.txt`, returning the headword and parsed
+/// [`Field`] on success. On failure, the returned [`ParseError`] points at
+/// the exact byte where the grammar stopped matching.
+pub fn parse_line(line_no: usize, text: &str) -> Result<(String, Field), ParseError> {
+    match entry(text) {
+        Ok(("", kv)) => Ok(kv),
+        Ok((rest, _)) => Err(ParseError {
+            line: line_no,
+            col: text.offset(rest) + 1,
+            message: format!("unexpected trailing input: {:?}", rest),
+        }),
+        Err(nom::Err::Error(e)) | Err(nom::Err::Failure(e)) => {
+            let (bad_input, kind) = e
+                .errors
+                .first()
+                .map(|(i, k)| (*i, k))
+                .unwrap_or((text, &VerboseErrorKind::Nom(nom::error::ErrorKind::Fail)));
+            Err(ParseError {
+                line: line_no,
+                col: text.offset(bad_input) + 1,
+                message: describe(kind),
+            })
+        }
+        Err(nom::Err::Incomplete(_)) => Err(ParseError {
+            line: line_no,
+            col: text.len() + 1,
+            message: "unexpected end of input".to_string(),
+        }),
+    }
+}
@@ -0,0 +1,270 @@
+//! A small predicate query language for filtering dictionary entries by
+//! structured criteria, e.g. `ident=名 & has-examples` selects noun entries
+//! that have at least one example sentence.
+//!
+//! [`Predicate`] is the evaluable AST; [`parse`] turns the compact textual
+//! form into one, and [`filter`] lazily runs a `Predicate` over a whole
+//! [`Dict`].
+
+use nom::{
+    branch::alt,
+    bytes::complete::{tag, take_till1},
+    character::complete::{char, multispace0},
+    combinator::{map, value},
+    multi::fold_many0,
+    sequence::{delimited, preceded},
+    IResult,
+};
+
+use crate::{Dict, Field};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Predicate {
+    IdentEquals(String),
+    BodyContains(String),
+    HasExamples,
+    HasComplement,
+    And(Vec<Predicate>),
+    Or(Vec<Predicate>),
+    Not(Box<Predicate>),
+}
+
+impl Predicate {
+    /// Evaluates this predicate against a single field.
+    pub fn eval(&self, field: &Field) -> bool {
+        match self {
+            Predicate::IdentEquals(want) => field.ident.as_deref() == Some(want.as_str()),
+            Predicate::BodyContains(needle) => {
+                field.explanation.body.contains(needle.as_str())
+                    || field
+                        .explanation
+                        .complements
+                        .iter()
+                        .any(|c| c.body.contains(needle.as_str()))
+            }
+            Predicate::HasExamples => !field.examples.is_empty(),
+            Predicate::HasComplement => !field.explanation.complements.is_empty(),
+            Predicate::And(preds) => preds.iter().all(|p| p.eval(field)),
+            Predicate::Or(preds) => preds.iter().any(|p| p.eval(field)),
+            Predicate::Not(pred) => !pred.eval(field),
+        }
+    }
+}
+
+/// Lazily filters every `(headword, Field)` pair in `dict` by `pred`.
+pub fn filter<'a>(
+    dict: &'a Dict,
+    pred: &'a Predicate,
+) -> impl Iterator<Item = (&'a str, &'a Field)> + 'a {
+    dict.headwords
+        .iter()
+        .zip(dict.fields.iter())
+        .flat_map(move |(headword, fields)| {
+            fields
+                .iter()
+                .filter(move |f| pred.eval(f))
+                .map(move |f| (headword.as_str(), f))
+        })
+}
+
+fn is_token_end(c: char) -> bool {
+    c.is_whitespace() || c == '&' || c == '|' || c == ')'
+}
+
+fn leaf(input: &str) -> IResult<&str, Predicate> {
+    alt((
+        value(Predicate::HasExamples, tag("has-examples")),
+        value(Predicate::HasComplement, tag("has-complement")),
+        map(
+            preceded(tag("ident="), take_till1(is_token_end)),
+            |s: &str| Predicate::IdentEquals(s.to_string()),
+        ),
+        map(preceded(tag("body~"), take_till1(is_token_end)), |s: &str| {
+            Predicate::BodyContains(s.to_string())
+        }),
+    ))(input)
+}
+
+fn atom(input: &str) -> IResult<&str, Predicate> {
+    let (input, _) = multispace0(input)?;
+    alt((
+        delimited(char('('), query, preceded(multispace0, char(')'))),
+        leaf,
+    ))(input)
+}
+
+fn not_expr(input: &str) -> IResult<&str, Predicate> {
+    let (input, _) = multispace0(input)?;
+    match char::<_, nom::error::Error<&str>>('!')(input) {
+        Ok((input, _)) => {
+            let (input, pred) = not_expr(input)?;
+            Ok((input, Predicate::Not(Box::new(pred))))
+        }
+        Err(_) => atom(input),
+    }
+}
+
+fn and_expr(input: &str) -> IResult<&str, Predicate> {
+    let (input, first) = not_expr(input)?;
+    fold_many0(
+        preceded(delimited(multispace0, char('&'), multispace0), not_expr),
+        move || first.clone(),
+        |acc, next| match acc {
+            Predicate::And(mut preds) => {
+                preds.push(next);
+                Predicate::And(preds)
+            }
+            acc => Predicate::And(vec![acc, next]),
+        },
+    )(input)
+}
+
+fn query(input: &str) -> IResult<&str, Predicate> {
+    let (input, first) = and_expr(input)?;
+    fold_many0(
+        preceded(delimited(multispace0, char('|'), multispace0), and_expr),
+        move || first.clone(),
+        |acc, next| match acc {
+            Predicate::Or(mut preds) => {
+                preds.push(next);
+                Predicate::Or(preds)
+            }
+            acc => Predicate::Or(vec![acc, next]),
+        },
+    )(input)
+}
+
+/// Parses a compact textual query such as `ident=名 & has-examples` into a
+/// [`Predicate`]. Leaves are `ident=VALUE`, `body~VALUE`, `has-examples`
+/// and `has-complement`; they combine with `&` (and), `|` (or), a `!`
+/// prefix (not) and parentheses for grouping.
+pub fn parse(input: &str) -> Result<Predicate, String> {
+    let (rest, pred) = query(input.trim()).map_err(|e| e.to_string())?;
+    let rest = rest.trim();
+    if !rest.is_empty() {
+        return Err(format!("unexpected trailing input: {:?}", rest));
+    }
+    Ok(pred)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn field(ident: Option<&str>, body: &str, has_complement: bool, has_example: bool) -> Field {
+        Field {
+            ident: ident.map(|s| s.to_string()),
+            explanation: crate::Explanation {
+                body: body.to_string(),
+                annotations: vec![],
+                complements: if has_complement {
+                    vec![crate::Complement {
+                        body: "追記".to_string(),
+                        annotations: vec![],
+                    }]
+                } else {
+                    vec![]
+                },
+            },
+            examples: if has_example {
+                vec![crate::Example {
+                    sentence: "example sentence".to_string(),
+                    complements: vec![],
+                }]
+            } else {
+                vec![]
+            },
+        }
+    }
+
+    #[test]
+    fn parses_leaves() {
+        assert_eq!(parse("has-examples").unwrap(), Predicate::HasExamples);
+        assert_eq!(parse("has-complement").unwrap(), Predicate::HasComplement);
+        assert_eq!(
+            parse("ident=名").unwrap(),
+            Predicate::IdentEquals("名".to_string())
+        );
+        assert_eq!(
+            parse("body~沈黙").unwrap(),
+            Predicate::BodyContains("沈黙".to_string())
+        );
+    }
+
+    #[test]
+    fn and_binds_tighter_than_or() {
+        // `a | b & c` should parse as `a | (b & c)`, not `(a | b) & c`.
+        let pred = parse("ident=a | ident=b & has-examples").unwrap();
+        assert_eq!(
+            pred,
+            Predicate::Or(vec![
+                Predicate::IdentEquals("a".to_string()),
+                Predicate::And(vec![
+                    Predicate::IdentEquals("b".to_string()),
+                    Predicate::HasExamples
+                ]),
+            ])
+        );
+    }
+
+    #[test]
+    fn parentheses_override_precedence() {
+        let pred = parse("(ident=a | ident=b) & has-examples").unwrap();
+        assert_eq!(
+            pred,
+            Predicate::And(vec![
+                Predicate::Or(vec![
+                    Predicate::IdentEquals("a".to_string()),
+                    Predicate::IdentEquals("b".to_string()),
+                ]),
+                Predicate::HasExamples,
+            ])
+        );
+    }
+
+    #[test]
+    fn negation_applies_to_the_following_atom_only() {
+        let pred = parse("!has-examples & has-complement").unwrap();
+        assert_eq!(
+            pred,
+            Predicate::And(vec![
+                Predicate::Not(Box::new(Predicate::HasExamples)),
+                Predicate::HasComplement,
+            ])
+        );
+    }
+
+    #[test]
+    fn rejects_trailing_garbage() {
+        assert!(parse("has-examples )").is_err());
+    }
+
+    #[test]
+    fn eval_matches_expected_fields() {
+        let noun_with_example = field(Some("名"), "body", false, true);
+        let verb_no_example = field(Some("動"), "body", false, false);
+
+        let pred = parse("ident=名 & has-examples").unwrap();
+        assert!(pred.eval(&noun_with_example));
+        assert!(!pred.eval(&verb_no_example));
+    }
+
+    #[test]
+    fn filter_yields_matching_headword_field_pairs() {
+        let dict = Dict {
+            keys: fst::Map::from_iter(Vec::<(String, u64)>::new()).unwrap(),
+            fields: vec![vec![
+                field(Some("名"), "body", false, true),
+                field(Some("動"), "body", false, false),
+            ]],
+            headwords: vec!["word".to_string()],
+            reverse: fst::Map::from_iter(Vec::<(String, u64)>::new()).unwrap(),
+            reverse_postings: vec![],
+        };
+        let pred = parse("has-examples").unwrap();
+        let hits: Vec<_> = filter(&dict, &pred).collect();
+        assert_eq!(hits.len(), 1);
+        assert_eq!(hits[0].0, "word");
+        assert_eq!(hits[0].1.ident.as_deref(), Some("名"));
+    }
+}
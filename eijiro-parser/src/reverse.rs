@@ -0,0 +1,199 @@
+//! Builds a Japanese -> English reverse index by indexing overlapping
+//! character n-grams drawn from each entry's explanation (and example
+//! sentences) against the `(headword_idx, field_idx)` pair that produced
+//! them, so a Japanese substring query can be matched back to the specific
+//! sense (not just the headword) whose text actually contains it.
+//!
+//! Japanese text has no word boundaries, so instead of tokenizing we index
+//! every overlapping n-gram of the configured widths and let the query side
+//! run the same automata (Levenshtein/prefix/subsequence) used for the
+//! forward index against these n-grams.
+
+use std::collections::BTreeMap;
+
+use fst::{Map, MapBuilder};
+
+use crate::Field;
+
+/// n-gram widths used when the caller doesn't ask for a specific set; 2-
+/// and 3-character windows recall most kanji compounds without blowing up
+/// the index the way wider windows would.
+pub const DEFAULT_NGRAM_WIDTHS: &[usize] = &[2, 3];
+
+/// Slides the same n-gram windows `build` indexes with over a query string,
+/// so a reverse-index lookup can be decomposed into the same vocabulary the
+/// index was built from rather than matching the raw query against it
+/// (`dict.reverse`'s keys are only ever 2-3 characters long). Returns the
+/// distinct grams in the order they occur.
+pub fn query_ngrams(query: &str, ngram_widths: &[usize]) -> Vec<String> {
+    let chars: Vec<char> = query.chars().collect();
+    let mut seen = std::collections::BTreeSet::new();
+    let mut grams = Vec::new();
+    for &width in ngram_widths {
+        if width == 0 || chars.len() < width {
+            continue;
+        }
+        for window in chars.windows(width) {
+            let gram: String = window.iter().collect();
+            if seen.insert(gram.clone()) {
+                grams.push(gram);
+            }
+        }
+    }
+    grams
+}
+
+/// Builds the reverse index over `fields` (indexed the same way as
+/// `Dict::fields`). Returns an FST mapping each n-gram to an offset into
+/// the returned postings list, where `postings[offset]` is the sorted,
+/// deduped list of `(headword_idx, field_idx)` pairs into `fields` whose
+/// explanation or examples contain that n-gram.
+pub fn build(
+    fields: &[Vec<Field>],
+    ngram_widths: &[usize],
+) -> (Map<Vec<u8>>, Vec<Vec<(u64, u64)>>) {
+    let mut index: BTreeMap<String, Vec<(u64, u64)>> = BTreeMap::new();
+
+    for (headword_idx, entries) in fields.iter().enumerate() {
+        for (field_idx, field) in entries.iter().enumerate() {
+            let key = (headword_idx as u64, field_idx as u64);
+            let mut texts = vec![field.explanation.body.as_str()];
+            texts.extend(field.examples.iter().map(|e| e.sentence.as_str()));
+            for text in texts {
+                let chars: Vec<char> = text.chars().collect();
+                for &width in ngram_widths {
+                    if width == 0 || chars.len() < width {
+                        continue;
+                    }
+                    for window in chars.windows(width) {
+                        let gram: String = window.iter().collect();
+                        let postings = index.entry(gram).or_default();
+                        if postings.last() != Some(&key) {
+                            postings.push(key);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    let mut builder = MapBuilder::memory();
+    let mut postings = Vec::with_capacity(index.len());
+    for (gram, idxs) in index {
+        builder
+            .insert(&gram, postings.len() as u64)
+            .expect("BTreeMap yields keys in sorted order");
+        postings.push(idxs);
+    }
+    (builder.into_map(), postings)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Example, Explanation, Field};
+
+    fn field(body: &str) -> Field {
+        Field {
+            ident: None,
+            explanation: Explanation {
+                body: body.to_string(),
+                annotations: vec![],
+                complements: vec![],
+            },
+            examples: vec![],
+        }
+    }
+
+    fn field_with_example(body: &str, example: &str) -> Field {
+        let mut f = field(body);
+        f.examples.push(Example {
+            sentence: example.to_string(),
+            complements: vec![],
+        });
+        f
+    }
+
+    fn postings_for(
+        map: &Map<Vec<u8>>,
+        postings: &[Vec<(u64, u64)>],
+        gram: &str,
+    ) -> Vec<(u64, u64)> {
+        map.get(gram)
+            .map(|offset| postings[offset as usize].clone())
+            .unwrap_or_default()
+    }
+
+    #[test]
+    fn indexes_every_ngram_width() {
+        let fields = vec![vec![field("自動補完")]];
+        let (map, postings) = build(&fields, DEFAULT_NGRAM_WIDTHS);
+        // width 2 and width 3 windows both present.
+        assert_eq!(postings_for(&map, &postings, "自動"), vec![(0, 0)]);
+        assert_eq!(postings_for(&map, &postings, "自動補"), vec![(0, 0)]);
+        assert_eq!(postings_for(&map, &postings, "動補完"), vec![(0, 0)]);
+        // a gram that never occurs isn't indexed at all.
+        assert!(map.get("沈黙").is_none());
+    }
+
+    #[test]
+    fn dedups_repeated_ngrams_within_one_entry() {
+        // "あいあい" contains "あい" twice; the entry should still post once.
+        let fields = vec![vec![field("あいあい")]];
+        let (map, postings) = build(&fields, &[2]);
+        assert_eq!(postings_for(&map, &postings, "あい"), vec![(0, 0)]);
+    }
+
+    #[test]
+    fn indexes_example_sentences_as_well_as_the_explanation() {
+        let fields = vec![vec![field_with_example("説明", "例文です")]];
+        let (map, postings) = build(&fields, &[2]);
+        assert_eq!(postings_for(&map, &postings, "例文"), vec![(0, 0)]);
+    }
+
+    #[test]
+    fn postings_cover_multiple_entries_sorted_by_index() {
+        let fields = vec![vec![field("同じ")], vec![field("同じ")]];
+        let (map, postings) = build(&fields, &[2]);
+        assert_eq!(postings_for(&map, &postings, "同じ"), vec![(0, 0), (1, 0)]);
+    }
+
+    #[test]
+    fn zero_width_is_ignored() {
+        let fields = vec![vec![field("あ")]];
+        let (map, _postings) = build(&fields, &[0, 2]);
+        assert!(map.get("あ").is_none());
+    }
+
+    #[test]
+    fn postings_are_keyed_per_field_not_per_headword() {
+        // Two senses of the same headword: only the first one's text
+        // contains the query gram, so only (headword_idx=0, field_idx=0)
+        // should post, not field_idx=1 as well.
+        let fields = vec![vec![field("桜の花が咲く"), field("全く関係ない話題")]];
+        let (map, postings) = build(&fields, &[2]);
+        assert_eq!(postings_for(&map, &postings, "桜の"), vec![(0, 0)]);
+        assert!(map.get("全く").is_some());
+        assert_eq!(postings_for(&map, &postings, "全く"), vec![(0, 1)]);
+    }
+
+    #[test]
+    fn query_ngrams_slides_every_configured_width() {
+        let grams = query_ngrams("自動補完", &[2, 3]);
+        assert_eq!(
+            grams,
+            vec!["自動", "動補", "補完", "自動補", "動補完"]
+        );
+    }
+
+    #[test]
+    fn query_ngrams_dedups_and_skips_widths_longer_than_the_query() {
+        let grams = query_ngrams("あいあ", &[2, 5]);
+        assert_eq!(grams, vec!["あい", "いあ"]);
+    }
+
+    #[test]
+    fn query_ngrams_empty_when_query_shorter_than_every_width() {
+        assert!(query_ngrams("あ", DEFAULT_NGRAM_WIDTHS).is_empty());
+    }
+}
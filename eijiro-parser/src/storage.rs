@@ -0,0 +1,183 @@
+//! Lazy, memory-mapped dictionary storage.
+//!
+//! [`crate::Dict`] loads every entry into memory up front, which wastes a
+//! lot of RAM once the dictionary has well over a million entries and a
+//! lookup only ever touches a handful of them. [`LazyDict`] instead keeps
+//! the key FST memory-mapped and stores each key's `Vec<Field>` in a
+//! separate companion file, with the FST value holding the byte offset of
+//! its bincode-encoded record; a `Vec<Field>` is only decoded once its key
+//! matches a query.
+//!
+//! The on-disk layout is two files sharing a path prefix:
+//!   - `<prefix>.fst`: the key FST, value = byte offset into `.fields`
+//!   - `<prefix>.fields`: back-to-back bincode-encoded `Vec<Field>` records
+//!     (bincode already length-prefixes its `Vec` encoding, so no extra
+//!     framing is needed to know where one record ends and the next
+//!     begins)
+
+use std::fs::File;
+use std::io::Write;
+use std::path::Path;
+
+use anyhow::{anyhow, Result};
+use fst::{Map, MapBuilder};
+use memmap2::Mmap;
+
+use crate::{Dict, Field};
+
+pub struct LazyDict {
+    pub keys: Map<Mmap>,
+    fields: Mmap,
+}
+
+impl LazyDict {
+    /// Opens a dictionary previously written by [`build`], memory-mapping
+    /// both `<path_prefix>.fst` and `<path_prefix>.fields` instead of
+    /// reading them into memory.
+    pub fn open<P: AsRef<Path>>(path_prefix: P) -> Result<LazyDict> {
+        let prefix = path_prefix.as_ref();
+
+        let fst_file = File::open(prefix.with_extension("fst"))?;
+        // Safety: the mapped file is only ever written by `build`, below,
+        // and isn't expected to be mutated concurrently with a reader.
+        let fst_mmap = unsafe { Mmap::map(&fst_file)? };
+        let keys = Map::new(fst_mmap).map_err(|e| anyhow!("corrupt key FST: {}", e))?;
+
+        let fields_file = File::open(prefix.with_extension("fields"))?;
+        let fields = unsafe { Mmap::map(&fields_file)? };
+
+        Ok(LazyDict { keys, fields })
+    }
+
+    /// Decodes the `Vec<Field>` whose record starts at `offset`, i.e. the
+    /// value looked up from `self.keys` for some headword.
+    pub fn fields_at(&self, offset: u64) -> Result<Vec<Field>> {
+        let bytes = self
+            .fields
+            .get(offset as usize..)
+            .ok_or_else(|| anyhow!("fields offset {} is out of bounds", offset))?;
+        bincode::deserialize(bytes)
+            .map_err(|e| anyhow!("corrupt fields record at offset {}: {}", offset, e))
+    }
+}
+
+/// Writes an eagerly-loaded [`Dict`] out as the two companion files that
+/// [`LazyDict::open`] expects, alongside `path_prefix`.
+pub fn build<P: AsRef<Path>>(dict: &Dict, path_prefix: P) -> Result<()> {
+    let prefix = path_prefix.as_ref();
+
+    let mut fields_file = File::create(prefix.with_extension("fields"))?;
+    let mut map = MapBuilder::memory();
+    let mut offset = 0u64;
+    for (key, fields) in dict.headwords.iter().zip(dict.fields.iter()) {
+        let bytes = bincode::serialize(fields)?;
+        map.insert(key, offset)
+            .map_err(|e| anyhow!("headword {:?}: {}", key, e))?;
+        fields_file.write_all(&bytes)?;
+        offset += bytes.len() as u64;
+    }
+
+    let fst_bytes = map.into_inner()?;
+    std::fs::write(prefix.with_extension("fst"), fst_bytes)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Explanation, Field};
+
+    fn field(ident: &str, body: &str) -> Field {
+        Field {
+            ident: Some(ident.to_string()),
+            explanation: Explanation {
+                body: body.to_string(),
+                annotations: vec![],
+                complements: vec![],
+            },
+            examples: vec![],
+        }
+    }
+
+    /// A path prefix under the OS temp dir, unique to this test process so
+    /// parallel test runs don't clobber each other's companion files.
+    fn temp_prefix(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("eijiro-storage-test-{}-{}", std::process::id(), name))
+    }
+
+    fn cleanup(prefix: &Path) {
+        let _ = std::fs::remove_file(prefix.with_extension("fst"));
+        let _ = std::fs::remove_file(prefix.with_extension("fields"));
+    }
+
+    #[test]
+    fn round_trips_fields_through_build_and_open() {
+        let prefix = temp_prefix("round-trip");
+        cleanup(&prefix);
+
+        let dict = Dict {
+            keys: Map::from_iter(Vec::<(String, u64)>::new()).unwrap(),
+            fields: vec![
+                vec![field("名", "一つ目")],
+                vec![field("動", "二つ目"), field("形", "二つ目の二")],
+            ],
+            headwords: vec!["one".to_string(), "two".to_string()],
+            reverse: Map::from_iter(Vec::<(String, u64)>::new()).unwrap(),
+            reverse_postings: vec![],
+        };
+
+        build(&dict, &prefix).unwrap();
+        let lazy = LazyDict::open(&prefix).unwrap();
+
+        use fst::Streamer;
+        let mut stream = lazy.keys.stream();
+        let mut seen = Vec::new();
+        while let Some((k, offset)) = stream.next() {
+            let k = std::str::from_utf8(k).unwrap().to_string();
+            let fields = lazy.fields_at(offset).unwrap();
+            seen.push((k, fields));
+        }
+        seen.sort_by(|a, b| a.0.cmp(&b.0));
+
+        assert_eq!(
+            seen,
+            vec![
+                ("one".to_string(), vec![field("名", "一つ目")]),
+                (
+                    "two".to_string(),
+                    vec![field("動", "二つ目"), field("形", "二つ目の二")]
+                ),
+            ]
+        );
+
+        cleanup(&prefix);
+    }
+
+    #[test]
+    fn fields_at_out_of_bounds_offset_is_an_error() {
+        let prefix = temp_prefix("out-of-bounds");
+        cleanup(&prefix);
+
+        let dict = Dict {
+            keys: Map::from_iter(Vec::<(String, u64)>::new()).unwrap(),
+            fields: vec![vec![field("名", "body")]],
+            headwords: vec!["word".to_string()],
+            reverse: Map::from_iter(Vec::<(String, u64)>::new()).unwrap(),
+            reverse_postings: vec![],
+        };
+        build(&dict, &prefix).unwrap();
+        let lazy = LazyDict::open(&prefix).unwrap();
+
+        assert!(lazy.fields_at(1_000_000).is_err());
+
+        cleanup(&prefix);
+    }
+
+    #[test]
+    fn open_fails_when_companion_files_are_missing() {
+        let prefix = temp_prefix("missing");
+        cleanup(&prefix);
+        assert!(LazyDict::open(&prefix).is_err());
+    }
+}
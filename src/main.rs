@@ -1,27 +1,46 @@
 use anyhow::{anyhow, ensure, Result};
-use clap::{App, Arg, ArgMatches, SubCommand};
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
 use std::io::prelude::*;
 use std::path::{Path, PathBuf};
 
 use eijiro_parser::fst;
-use fst::{IntoStreamer, Streamer};
+use fst::{Automaton, IntoStreamer, Streamer};
 
 use log::{error, info, warn};
 
+fn annotation_str(a: &eijiro_parser::Annotation) -> String {
+    use eijiro_parser::Annotation;
+    match a {
+        Annotation::Category { kind, value } => format!("【{}】{}", kind, value),
+        Annotation::Domain(d) => format!("《{}》", d),
+        Annotation::Register(r) => format!("〈{}〉", r),
+        Annotation::Context(c) => format!("〔{}〕", c),
+    }
+}
+
+fn complement_str(c: &eijiro_parser::Complement) -> String {
+    format!(
+        "◆{}",
+        eijiro_parser::reinsert_annotations(&c.body, &c.annotations, annotation_str)
+    )
+}
+
 fn printer(key: &str, field: &eijiro_parser::Field) -> String {
     format!(
         "{} {{{}}} : {}{}{}",
         key,
         field.ident.as_ref().unwrap_or(&"".to_string()),
-        field.explanation.body,
+        eijiro_parser::reinsert_annotations(
+            &field.explanation.body,
+            &field.explanation.annotations,
+            annotation_str
+        ),
         field
             .explanation
             .complements
             .iter()
-            .fold("".to_string(), |mut p, c| {
-                p += &format!("â—†{}", c.body);
-                p
-            }),
+            .map(complement_str)
+            .collect::<String>(),
         field.examples.iter().fold("".to_string(), |mut p, e| {
             p += &format!("\n        {}", e.sentence);
             p
@@ -29,17 +48,91 @@ fn printer(key: &str, field: &eijiro_parser::Field) -> String {
     )
 }
 
-fn main() {
-    pretty_env_logger::init();
-    let app = App::new("eijiro-rs")
-        .version("0.1.0")
-        .author("algon-320 <algon.0320@mail.com>")
-        .about("English-Japanese dictionary (using Eijiro)")
-        .arg(Arg::with_name("word").required(true));
-    let matches = app.get_matches();
-    let word = matches.value_of("word").unwrap();
+/// Maximum number of results printed, after ranking by match quality.
+const MAX_RESULTS: usize = 50;
+
+/// Plain byte-wise Levenshtein distance, used only to rank results by match
+/// quality (the fst automaton already did the real filtering).
+fn levenshtein(a: &str, b: &str) -> usize {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for i in 1..=a.len() {
+        let mut prev = row[0];
+        row[0] = i;
+        for j in 1..=b.len() {
+            let tmp = row[j];
+            row[j] = if a[i - 1] == b[j - 1] {
+                prev
+            } else {
+                1 + prev.min(row[j]).min(row[j - 1])
+            };
+            prev = tmp;
+        }
+    }
+    row[b.len()]
+}
+
+/// Runs `automaton` against `map` and collects every (key, value) match.
+/// Generic over the FST's backing storage so it works against both the
+/// in-memory `Map<Vec<u8>>` built by an eager [`eijiro_parser::Dict`] and the
+/// memory-mapped `Map<Mmap>` behind [`eijiro_parser::LazyDict`].
+fn search_with<D: AsRef<[u8]>, A: fst::Automaton>(map: &fst::Map<D>, automaton: A) -> Vec<(String, u64)> {
+    let mut stream = map.search(&automaton).into_stream();
+    let mut results = Vec::new();
+    while let Some((k, idx)) = stream.next() {
+        results.push((std::str::from_utf8(k).unwrap().to_string(), idx));
+    }
+    results
+}
+
+/// Searches `map` for `term`, composing the Levenshtein/prefix/subsequence
+/// automata the same way regardless of whether `term` is the user's whole
+/// query (forward search) or a single n-gram of it (reverse search).
+/// --prefix/--subsequence alone mean completion-style search, not "exact
+/// match" (distance 0) narrowed by prefix/subsequence; only bring in the
+/// Levenshtein automaton when the caller actually asked for a distance.
+fn search_term<D: AsRef<[u8]>>(
+    map: &fst::Map<D>,
+    term: &str,
+    prefix: bool,
+    subsequence: bool,
+    explicit_distance: bool,
+    distance: u32,
+) -> Vec<(String, u64)> {
+    if (prefix || subsequence) && !explicit_distance {
+        match (prefix, subsequence) {
+            (true, false) => search_with(map, fst::automaton::Str::new(term).starts_with()),
+            (false, true) => search_with(map, fst::automaton::Subsequence::new(term)),
+            (true, true) => search_with(
+                map,
+                fst::automaton::Str::new(term)
+                    .starts_with()
+                    .intersection(fst::automaton::Subsequence::new(term)),
+            ),
+            (false, false) => unreachable!(),
+        }
+    } else {
+        let lev = fst::automaton::Levenshtein::new(term, distance).unwrap();
+        match (prefix, subsequence) {
+            (false, false) => search_with(map, lev),
+            (true, false) => {
+                search_with(map, lev.intersection(fst::automaton::Str::new(term).starts_with()))
+            }
+            (false, true) => {
+                search_with(map, lev.intersection(fst::automaton::Subsequence::new(term)))
+            }
+            (true, true) => search_with(
+                map,
+                lev.intersection(fst::automaton::Str::new(term).starts_with())
+                    .intersection(fst::automaton::Subsequence::new(term)),
+            ),
+        }
+    }
+}
 
-    let dict = match std::fs::read("./dict_dump.bincode") {
+fn load_dict() -> eijiro_parser::Dict {
+    match std::fs::read("./dict_dump.bincode") {
         Ok(bytes) => {
             info!("Loading dict");
             let dict = bincode::deserialize(&bytes).unwrap();
@@ -53,15 +146,155 @@ fn main() {
             let _ = std::fs::write("./dict_dump.bincode", bincode::serialize(&dict).unwrap());
             dict
         }
-    };
+    }
+}
+
+/// Opens the memory-mapped dictionary at `prefix`, building it from a full
+/// (eager) parse the first time it's needed. This is the hot path for plain
+/// forward lookups: once the companion files exist, a lookup only ever
+/// mmaps the key FST and decodes the handful of `Vec<Field>` records the
+/// query actually matches, instead of deserializing the whole dictionary.
+fn open_or_build_lazy(prefix: &str) -> eijiro_parser::LazyDict {
+    match eijiro_parser::LazyDict::open(prefix) {
+        Ok(lazy) => lazy,
+        Err(_) => {
+            info!("Building lazy dict at {}", prefix);
+            let dict = load_dict();
+            eijiro_parser::build_lazy(&dict, prefix).expect("failed to build lazy dict");
+            eijiro_parser::LazyDict::open(prefix).expect("just built the lazy dict")
+        }
+    }
+}
+
+fn run_filter(matches: &ArgMatches) {
+    let query = matches.value_of("query").unwrap();
+    let pred = eijiro_parser::parse_query(query).unwrap_or_else(|e| {
+        eprintln!("invalid query: {}", e);
+        std::process::exit(1);
+    });
+
+    let dict = load_dict();
+    for (hit_idx, (key, field)) in eijiro_parser::filter(&dict, &pred).enumerate() {
+        println!("[{:3}] {}", hit_idx, printer(key, field));
+    }
+}
+
+fn main() {
+    pretty_env_logger::init();
+    let app = App::new("eijiro-rs")
+        .version("0.1.0")
+        .author("algon-320 <algon.0320@mail.com>")
+        .about("English-Japanese dictionary (using Eijiro)")
+        .setting(AppSettings::SubcommandsNegateReqs)
+        .arg(Arg::with_name("word").required(true))
+        .arg(
+            Arg::with_name("distance")
+                .long("distance")
+                .takes_value(true)
+                .help(
+                    "maximum Levenshtein edit distance for fuzzy matching \
+                     (default: 0, or unbounded if --prefix/--subsequence is given alone)",
+                ),
+        )
+        .arg(
+            Arg::with_name("prefix")
+                .long("prefix")
+                .help("match keys that start with <word>, for completion-style lookup"),
+        )
+        .arg(
+            Arg::with_name("subsequence")
+                .long("subsequence")
+                .help("match keys that contain the characters of <word>, in order"),
+        )
+        .arg(
+            Arg::with_name("reverse")
+                .long("reverse")
+                .help("search the Japanese reverse index instead of English headwords"),
+        )
+        .subcommand(
+            SubCommand::with_name("filter")
+                .about("filter entries by a predicate query, e.g. `ident=名 & has-examples`")
+                .arg(Arg::with_name("query").required(true)),
+        );
+    let matches = app.get_matches();
+
+    if let Some(filter_matches) = matches.subcommand_matches("filter") {
+        run_filter(filter_matches);
+        return;
+    }
+
+    let word = matches.value_of("word").unwrap();
+    let explicit_distance = matches.is_present("distance");
+    let distance: u32 = matches.value_of("distance").unwrap_or("0").parse().unwrap_or_else(|_| {
+        eprintln!("--distance must be a non-negative integer");
+        std::process::exit(1);
+    });
+    let prefix = matches.is_present("prefix");
+    let subsequence = matches.is_present("subsequence");
+    let reverse = matches.is_present("reverse");
+
+    // --reverse needs `dict.reverse`/`reverse_postings`, which the lazy
+    // on-disk layout doesn't store, so it falls back to a full eager parse.
+    if reverse {
+        let dict = load_dict();
+
+        // `dict.reverse`'s keys are only the 2-3 character n-grams `build`
+        // indexed, so the query has to be decomposed into those same grams
+        // rather than matched against the index whole. A real hit contains
+        // every gram the query tiles into, so intersect the postings each
+        // gram resolves to instead of just unioning them.
+        let grams = eijiro_parser::query_ngrams(word, eijiro_parser::DEFAULT_NGRAM_WIDTHS);
+        let grams: Vec<String> = if grams.is_empty() {
+            vec![word.to_string()]
+        } else {
+            grams
+        };
+
+        let mut postings_sets = grams.iter().map(|gram| {
+            let gram_hits = search_term(&dict.reverse, gram, prefix, subsequence, explicit_distance, distance);
+            let mut idxs = std::collections::BTreeSet::new();
+            for (_, offset) in gram_hits {
+                idxs.extend(dict.reverse_postings[offset as usize].iter().copied());
+            }
+            idxs
+        });
+        let mut seen = postings_sets.next().unwrap_or_default();
+        for set in postings_sets {
+            seen = seen.intersection(&set).copied().collect();
+        }
+
+        // Each posting is a specific (headword, sense) pair, not every
+        // sense sharing the headword, so only print the `Field` that
+        // actually matched.
+        let mut results: Vec<(String, u64, u64)> = seen
+            .into_iter()
+            .map(|(headword_idx, field_idx)| {
+                (dict.headwords[headword_idx as usize].clone(), headword_idx, field_idx)
+            })
+            .collect();
+        results.sort_by_key(|(k, _, _)| (levenshtein(word, k), k.len(), k.clone()));
+        results.truncate(MAX_RESULTS);
+
+        let mut hit_idx = 0;
+        for (item, headword_idx, field_idx) in &results {
+            let f = &dict.fields[*headword_idx as usize][*field_idx as usize];
+            println!("[{:3}] {}", hit_idx, printer(item, f));
+            hit_idx += 1;
+        }
+        return;
+    }
+
+    let lazy = open_or_build_lazy("./dict_dump");
+    let mut results = search_term(&lazy.keys, word, prefix, subsequence, explicit_distance, distance);
+    results.sort_by_key(|(k, _)| (levenshtein(word, k), k.len(), k.clone()));
+    results.truncate(MAX_RESULTS);
 
-    let matcher = fst::automaton::Levenshtein::new(word, 0).unwrap();
-    let mut stream = dict.keys.search(&matcher).into_stream();
     let mut hit_idx = 0;
-    while let Some((k, idx)) = stream.next() {
-        let item = std::str::from_utf8(k).unwrap();
-        // println!("{}: {} : {:#?}", hit_idx, item, &dict.fields[idx as usize]);
-        for f in &dict.fields[idx as usize] {
+    for (item, offset) in &results {
+        let fields = lazy
+            .fields_at(*offset)
+            .expect("corrupt lazy fields record");
+        for f in &fields {
             println!("[{:3}] {}", hit_idx, printer(item, f));
             hit_idx += 1;
         }